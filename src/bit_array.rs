@@ -0,0 +1,174 @@
+//! Bit-Packed Sets
+//!
+//! Compact, allocation-free membership sets used to avoid the `BTreeMap`/linear-scan overhead
+//! that `PerfectMatching::legacy_get_mwpm_result` and the parallel primal module's fusion step
+//! would otherwise pay: tracking "is this vertex covered" or "does this partition's boundary
+//! overlap that dual node's support" only needs a handful of word-sized operations.
+//!
+
+const BITS_PER_WORD: usize = 64;
+
+/// a growable bit set backed by a `Vec<u64>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitVector {
+    words: Vec<u64>,
+}
+
+fn word_and_mask(index: usize) -> (usize, u64) {
+    (index / BITS_PER_WORD, 1u64 << (index % BITS_PER_WORD))
+}
+
+impl BitVector {
+
+    /// create a set with enough backing storage to hold indices up to `capacity` (exclusive)
+    pub fn new(capacity: usize) -> Self {
+        Self { words: vec![0; (capacity + BITS_PER_WORD - 1) / BITS_PER_WORD] }
+    }
+
+    fn ensure_capacity(&mut self, word_index: usize) {
+        if self.words.len() <= word_index {
+            self.words.resize(word_index + 1, 0);
+        }
+    }
+
+    pub fn set(&mut self, index: usize) {
+        let (word_index, mask) = word_and_mask(index);
+        self.ensure_capacity(word_index);
+        self.words[word_index] |= mask;
+    }
+
+    pub fn unset(&mut self, index: usize) {
+        let (word_index, mask) = word_and_mask(index);
+        if word_index < self.words.len() {
+            self.words[word_index] &= !mask;
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        let (word_index, mask) = word_and_mask(index);
+        self.words.get(word_index).map_or(false, |word| word & mask != 0)
+    }
+
+    /// true iff `self` and `other` have at least one bit in common, checked a whole word at a time
+    pub fn intersects(&self, other: &BitVector) -> bool {
+        self.words.iter().zip(other.words.iter()).any(|(a, b)| a & b != 0)
+    }
+
+    /// union `other` into `self`, returning whether `self` actually gained any new bit
+    pub fn union_with_changed(&mut self, other: &BitVector) -> bool {
+        self.ensure_capacity(other.words.len().saturating_sub(1));
+        let mut changed = false;
+        for (word, other_word) in self.words.iter_mut().zip(other.words.iter()) {
+            let merged = *word | *other_word;
+            if merged != *word {
+                changed = true;
+            }
+            *word = merged;
+        }
+        changed
+    }
+
+    /// iterate over the indices of every set bit, in ascending order
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(word_index, &word)| {
+            (0..BITS_PER_WORD).filter(move |bit| word & (1u64 << bit) != 0).map(move |bit| word_index * BITS_PER_WORD + bit)
+        })
+    }
+
+}
+
+/// a row-major matrix of bits, e.g. "which vertices are covered by which blossom/unit"
+#[derive(Debug, Clone)]
+pub struct BitMatrix {
+    rows: Vec<BitVector>,
+    columns: usize,
+}
+
+impl BitMatrix {
+
+    pub fn new(row_count: usize, columns: usize) -> Self {
+        Self { rows: (0..row_count).map(|_| BitVector::new(columns)).collect(), columns }
+    }
+
+    pub fn set(&mut self, row: usize, column: usize) {
+        debug_assert!(column < self.columns);
+        self.rows[row].set(column);
+    }
+
+    pub fn contains(&self, row: usize, column: usize) -> bool {
+        self.rows[row].contains(column)
+    }
+
+    pub fn row(&self, row: usize) -> &BitVector {
+        &self.rows[row]
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_contains() {
+        let mut bits = BitVector::new(10);
+        bits.set(3);
+        bits.set(130);  // past the initial word, exercises `ensure_capacity`
+        assert!(bits.contains(3));
+        assert!(bits.contains(130));
+        assert!(!bits.contains(4));
+    }
+
+    #[test]
+    fn unset_clears_a_bit() {
+        let mut bits = BitVector::new(10);
+        bits.set(5);
+        bits.unset(5);
+        assert!(!bits.contains(5));
+    }
+
+    #[test]
+    fn intersects_detects_shared_bits() {
+        let mut a = BitVector::new(200);
+        let mut b = BitVector::new(200);
+        a.set(1);
+        a.set(100);
+        b.set(2);
+        assert!(!a.intersects(&b));
+        b.set(100);
+        assert!(a.intersects(&b));
+    }
+
+    #[test]
+    fn union_with_changed_reports_whether_anything_was_added() {
+        let mut a = BitVector::new(10);
+        let mut b = BitVector::new(10);
+        a.set(1);
+        b.set(1);
+        assert!(!a.union_with_changed(&b), "unioning an already-contained bit should report no change");
+        b.set(2);
+        assert!(a.union_with_changed(&b), "unioning a new bit should report a change");
+        assert!(a.contains(2));
+    }
+
+    #[test]
+    fn iter_ones_yields_every_set_bit_in_order() {
+        let mut bits = BitVector::new(200);
+        for i in [0, 63, 64, 150] {
+            bits.set(i);
+        }
+        assert_eq!(bits.iter_ones().collect::<Vec<_>>(), vec![0, 63, 64, 150]);
+    }
+
+    #[test]
+    fn matrix_rows_are_independent() {
+        let mut matrix = BitMatrix::new(3, 16);
+        matrix.set(0, 5);
+        matrix.set(2, 5);
+        assert!(matrix.contains(0, 5));
+        assert!(!matrix.contains(1, 5));
+        assert!(matrix.contains(2, 5));
+        assert!(matrix.row(0).intersects(matrix.row(2)));
+        assert!(!matrix.row(0).intersects(matrix.row(1)));
+    }
+}
@@ -5,8 +5,9 @@
 
 use super::util::*;
 use super::dual_module::*;
+use super::bit_array::*;
 use crate::derivative::Derivative;
-use std::collections::BTreeMap;
+use std::collections::HashSet;
 
 
 #[derive(Derivative)]
@@ -142,7 +143,9 @@ impl PerfectMatching {
 
     /// this interface is not very optimized, but is compatible with blossom V algorithm's result
     pub fn legacy_get_mwpm_result(&self, syndrome_vertices: &Vec<usize>) -> Vec<usize> {
-        let mut peer_matching_maps = BTreeMap::<usize, usize>::new();
+        // collect every (syndrome vertex, matched target) pair first so we know how large the
+        // direct-index lookup table needs to be
+        let mut matched_pairs = Vec::with_capacity(self.peer_matchings.len() * 2 + self.virtual_matchings.len());
         for (ptr_1, ptr_2) in self.peer_matchings.iter() {
             let a_vid = {
                 let node = ptr_1.read_recursive();
@@ -152,26 +155,159 @@ impl PerfectMatching {
                 let node = ptr_2.read_recursive();
                 if let DualNodeClass::SyndromeVertex{ syndrome_index } = &node.class { *syndrome_index } else { unreachable!("can only be syndrome") }
             };
-            peer_matching_maps.insert(a_vid, b_vid);
-            peer_matching_maps.insert(b_vid, a_vid);
+            matched_pairs.push((a_vid, b_vid));
+            matched_pairs.push((b_vid, a_vid));
         }
-        let mut virtual_matching_maps = BTreeMap::<usize, usize>::new();
         for (ptr, virtual_vertex) in self.virtual_matchings.iter() {
             let a_vid = {
                 let node = ptr.read_recursive();
                 if let DualNodeClass::SyndromeVertex{ syndrome_index } = &node.class { *syndrome_index } else { unreachable!("can only be syndrome") }
             };
-            virtual_matching_maps.insert(a_vid, *virtual_vertex);
+            matched_pairs.push((a_vid, *virtual_vertex));
+        }
+        let max_vertex_index = matched_pairs.iter().map(|(a, _)| *a)
+            .chain(syndrome_vertices.iter().copied()).max().unwrap_or(0);
+        // `covered` tracks which syndrome vertices have a recorded match, so the final lookup can
+        // index directly into `matched_to` instead of going through a `BTreeMap`
+        let mut covered = BitVector::new(max_vertex_index + 1);
+        let mut matched_to = vec![0usize; max_vertex_index + 1];
+        for (a_vid, target) in matched_pairs.into_iter() {
+            matched_to[a_vid] = target;
+            covered.set(a_vid);
         }
         let mut mwpm_result = Vec::with_capacity(syndrome_vertices.len());
         for syndrome_vertex in syndrome_vertices.iter() {
-            if let Some(a) = peer_matching_maps.get(&syndrome_vertex) {
-                mwpm_result.push(*a);
-            } else if let Some(v) = virtual_matching_maps.get(&syndrome_vertex) {
-                mwpm_result.push(*v);
-            } else { panic!("cannot find syndrome vertex {}", syndrome_vertex) }
+            if !covered.contains(*syndrome_vertex) { panic!("cannot find syndrome vertex {}", syndrome_vertex) }
+            mwpm_result.push(matched_to[*syndrome_vertex]);
         }
         mwpm_result
     }
 
 }
+
+/// a node's label in the current alternating tree search forest: `Plus`-labelled nodes are an even
+/// number of tree edges from a tree root (free to grow), `Minus`-labelled nodes are an odd number
+/// (reached via their tentative match, on the way back up towards the root)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlternatingLabel { Plus, Minus }
+
+/// read-only view of the alternating tree / temporary matches that a primal module's `resolve`
+/// maintains (see the trait doc above), abstracted away from any concrete node type so that the
+/// backward search below can be driven by an in-memory test double instead of a real `DualNodePtr`
+pub trait AlternatingTreeView<N> {
+    /// the node `node` is currently tentatively matched to, if it's matched at all
+    fn matched_to(&self, node: &N) -> Option<N>;
+    /// `node`'s label in the current alternating tree search forest, if it's part of one
+    fn label(&self, node: &N) -> Option<AlternatingLabel>;
+    /// the blossom that directly contains `node`, if any (blossom nesting, independent of labels)
+    fn parent_blossom(&self, node: &N) -> Option<N>;
+}
+
+/// outcome of a bounded backward walk up the alternating tree from a newly-conflicting node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackwardSearchResult<N> {
+    /// a full augmenting path was found, from the conflicting node down to a free tree root
+    AugmentingPath(Vec<N>),
+    /// the walk closed back onto a node already on it: the nodes in between form a blossom cycle
+    BlossomCycle(Vec<N>),
+    /// the walk ran past `max_depth` hops, or hit a branch it couldn't classify (e.g. a `Minus`
+    /// label with no recorded match); the caller should fall back to the full (non-lazy) routine
+    Inconclusive,
+}
+
+/// how many "simple" hops the lazy backward search is willing to follow before giving up and
+/// telling the caller to fall back to the full routine
+pub const DEFAULT_BACKWARD_SEARCH_DEPTH: usize = 64;
+
+/// walk backward from a freshly-conflicting node, following only unconditional hops -- a
+/// `Minus`-labelled node's tentative match, or a blossom's nesting parent -- instead of rescanning
+/// the whole alternating tree. At each step: a `Minus`-labelled node is by construction already
+/// matched, so the walk takes that match as its one unconditional hop; any other node (`Plus`-labelled,
+/// or outside any tree at all) is a label boundary, so the only unconditional hop left is blossom
+/// nesting, and if there isn't one the walk stops there. The walk reports an augmenting path the
+/// moment it reaches such a boundary node with no enclosing blossom (a free tree root), a blossom
+/// cycle the moment it revisits a node already on the current walk, and otherwise bails out
+/// inconclusive past `max_depth` hops or at a `Minus` node with no recorded match -- exactly a
+/// limited DFS that only follows edges it's sure about. It works on any `AlternatingTreeView`;
+/// no `resolve` implementation or fusion step in this crate calls it.
+pub fn backward_augmenting_search<N: Clone + Eq + std::hash::Hash>(tree: &impl AlternatingTreeView<N>, start: &N, max_depth: usize) -> BackwardSearchResult<N> {
+    let mut path = vec![start.clone()];
+    let mut visited: HashSet<N> = HashSet::new();
+    visited.insert(start.clone());
+    let mut current = start.clone();
+    for hops in 0..=max_depth {
+        let next = match tree.label(&current) {
+            Some(AlternatingLabel::Minus) => match tree.matched_to(&current) {
+                Some(matched) => matched,
+                None => return BackwardSearchResult::Inconclusive,  // "-" node with no match: inconsistent, bail
+            },
+            _ => match tree.parent_blossom(&current) {
+                Some(parent) => parent,
+                None => return BackwardSearchResult::AugmentingPath(path),  // hit a free tree root
+            },
+        };
+        if hops == max_depth {
+            break;  // the landing node was examined, but one more hop would go past `max_depth`
+        }
+        if visited.contains(&next) {
+            return BackwardSearchResult::BlossomCycle(path);
+        }
+        visited.insert(next.clone());
+        path.push(next.clone());
+        current = next;
+    }
+    BackwardSearchResult::Inconclusive
+}
+
+#[cfg(test)]
+mod backward_search_tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// an in-memory stand-in for a primal module's alternating tree bookkeeping, keyed by plain
+    /// `u32` ids instead of real `DualNodePtr`s, so the search logic can be tested in isolation
+    #[derive(Default)]
+    struct FakeTree {
+        label: HashMap<u32, AlternatingLabel>,
+        matched: HashMap<u32, u32>,
+        parent_blossom: HashMap<u32, u32>,
+    }
+
+    impl AlternatingTreeView<u32> for FakeTree {
+        fn matched_to(&self, node: &u32) -> Option<u32> { self.matched.get(node).copied() }
+        fn label(&self, node: &u32) -> Option<AlternatingLabel> { self.label.get(node).copied() }
+        fn parent_blossom(&self, node: &u32) -> Option<u32> { self.parent_blossom.get(node).copied() }
+    }
+
+    #[test]
+    fn finds_augmenting_path() {
+        let mut tree = FakeTree::default();
+        tree.label.insert(3, AlternatingLabel::Minus);
+        tree.matched.insert(3, 2);  // 2 is unlabelled and has no parent blossom: a free tree root
+        assert_eq!(backward_augmenting_search(&tree, &3u32, 10), BackwardSearchResult::AugmentingPath(vec![3, 2]));
+    }
+
+    #[test]
+    fn finds_blossom_cycle() {
+        let mut tree = FakeTree::default();
+        tree.parent_blossom.insert(1, 2);
+        tree.parent_blossom.insert(2, 1);  // cycle back onto the starting node
+        assert_eq!(backward_augmenting_search(&tree, &1u32, 10), BackwardSearchResult::BlossomCycle(vec![1, 2]));
+    }
+
+    #[test]
+    fn bails_out_past_max_depth() {
+        let mut tree = FakeTree::default();
+        for i in 0..20u32 {
+            tree.parent_blossom.insert(i, i + 1);  // a long chain that never closes or ends
+        }
+        assert_eq!(backward_augmenting_search(&tree, &0u32, 5), BackwardSearchResult::Inconclusive);
+    }
+
+    #[test]
+    fn bails_out_on_unmatched_minus_label() {
+        let mut tree = FakeTree::default();
+        tree.label.insert(5, AlternatingLabel::Minus);  // "-" labelled but no recorded match: inconsistent
+        assert_eq!(backward_augmenting_search(&tree, &5u32, 10), BackwardSearchResult::Inconclusive);
+    }
+}
@@ -0,0 +1,202 @@
+//! Arena Allocation
+//!
+//! A typed bump allocator for primal book-keeping nodes. Currently used by
+//! [`super::primal_module_parallel`] to give each parallel unit reusable, chunked storage for its
+//! own per-leaf bookkeeping (e.g. which `DualNodePtr`s it owns), addressed by a small `Copy`
+//! `(unit_index, slot)` index instead of holding a `Vec` of cloned `Arc` handles that gets
+//! dropped and reallocated from scratch on every `clear()`. Note this does *not* change how
+//! `DualNode`s themselves are allocated, nor does `expand_blossom`/`expand_peer_matching` go
+//! through it -- both of those live in `dual_module.rs`/`primal_module_serial.rs`, which are
+//! outside this series' files.
+//!
+
+use std::mem::MaybeUninit;
+
+/// number of nodes stored in a single chunk; chosen as a convenient unit of amortized
+/// allocation without wasting too much memory when only a few nodes are needed
+const CHUNK_SIZE: usize = 256;
+
+/// a stable reference into a [`NodeArena`]; carries the *slot's own* generation at allocation time
+/// (bumped every time that exact slot is freed), not just the arena-wide one, so a stale index
+/// can't resolve to whatever new occupant a later `alloc` recycles the same slot into (the
+/// standard slotmap / generational-arena ABA guard)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ArenaIndex {
+    slot: usize,
+    generation: u32,
+}
+
+struct Chunk<Node> {
+    slots: Box<[MaybeUninit<Node>; CHUNK_SIZE]>,
+    /// tracks which slots currently hold a live `Node`, so the chunk's `Drop` only drops those
+    occupied: [bool; CHUNK_SIZE],
+    /// per-slot generation, bumped on every `free` of that slot; compared against `ArenaIndex::generation`
+    generations: [u32; CHUNK_SIZE],
+}
+
+impl<Node> Chunk<Node> {
+    fn new() -> Self {
+        Self {
+            slots: Box::new(unsafe { MaybeUninit::uninit().assume_init() }),
+            occupied: [false; CHUNK_SIZE],
+            generations: [0; CHUNK_SIZE],
+        }
+    }
+}
+
+impl<Node> Drop for Chunk<Node> {
+    fn drop(&mut self) {
+        for (i, occupied) in self.occupied.iter().enumerate() {
+            if *occupied {
+                unsafe { self.slots[i].assume_init_drop(); }
+            }
+        }
+    }
+}
+
+/// a typed bump allocator for book-keeping nodes: new nodes are handed out from fixed-size
+/// chunks and recycled slots are tracked on a free list; every slot carries its own generation
+/// counter, bumped whenever that slot is freed (by `free` or `clear`), so an `ArenaIndex` taken
+/// before a slot was recycled can never resolve to whatever new occupant later reused it;
+/// freeing a whole chunk (on `Drop`) releases its storage in one shot
+pub struct NodeArena<Node> {
+    chunks: Vec<Chunk<Node>>,
+    /// slots that have been freed and can be reused before bumping into fresh storage
+    free_list: Vec<usize>,
+    /// one past the highest slot ever handed out; grows the chunk vector lazily
+    high_water_mark: usize,
+}
+
+impl<Node> NodeArena<Node> {
+
+    pub fn new() -> Self {
+        Self { chunks: vec![], free_list: vec![], high_water_mark: 0 }
+    }
+
+    fn chunk_and_offset(slot: usize) -> (usize, usize) {
+        (slot / CHUNK_SIZE, slot % CHUNK_SIZE)
+    }
+
+    /// allocate a new node, returning a stable index that can be used to retrieve it later
+    pub fn alloc(&mut self, node: Node) -> ArenaIndex {
+        let slot = self.free_list.pop().unwrap_or_else(|| {
+            let slot = self.high_water_mark;
+            self.high_water_mark += 1;
+            slot
+        });
+        let (chunk_index, offset) = Self::chunk_and_offset(slot);
+        while self.chunks.len() <= chunk_index {
+            self.chunks.push(Chunk::new());
+        }
+        let chunk = &mut self.chunks[chunk_index];
+        chunk.slots[offset].write(node);
+        chunk.occupied[offset] = true;
+        // note: the slot's generation is *not* bumped here, only on `free`; a freshly recycled
+        // slot keeps whatever generation its previous occupant's `free` left it at, which is
+        // exactly what makes that previous occupant's `ArenaIndex` stale (different generation)
+        ArenaIndex { slot, generation: chunk.generations[offset] }
+    }
+
+    /// recycle a slot so a future `alloc` can reuse its storage
+    pub fn free(&mut self, index: ArenaIndex) {
+        let (chunk_index, offset) = Self::chunk_and_offset(index.slot);
+        let Some(chunk) = self.chunks.get_mut(chunk_index) else { return };
+        if !chunk.occupied[offset] || chunk.generations[offset] != index.generation {
+            return;  // stale index: already freed (possibly reused by a later `alloc`) or out of range
+        }
+        unsafe { chunk.slots[offset].assume_init_drop(); }
+        chunk.occupied[offset] = false;
+        chunk.generations[offset] = chunk.generations[offset].wrapping_add(1);
+        self.free_list.push(index.slot);
+    }
+
+    pub fn get(&self, index: ArenaIndex) -> Option<&Node> {
+        let (chunk_index, offset) = Self::chunk_and_offset(index.slot);
+        let chunk = self.chunks.get(chunk_index)?;
+        if !chunk.occupied[offset] || chunk.generations[offset] != index.generation {
+            return None;
+        }
+        Some(unsafe { chunk.slots[offset].assume_init_ref() })
+    }
+
+    pub fn get_mut(&mut self, index: ArenaIndex) -> Option<&mut Node> {
+        let (chunk_index, offset) = Self::chunk_and_offset(index.slot);
+        let chunk = self.chunks.get_mut(chunk_index)?;
+        if !chunk.occupied[offset] || chunk.generations[offset] != index.generation {
+            return None;
+        }
+        Some(unsafe { chunk.slots[offset].assume_init_mut() })
+    }
+
+    /// reset the arena for a new decoding problem: every currently-occupied slot is dropped and
+    /// its generation bumped (so any outstanding `ArenaIndex` into it is invalidated the same way
+    /// a `free` would invalidate it); the backing chunks keep their allocated storage so the next
+    /// problem reuses it
+    pub fn clear(&mut self) {
+        for chunk in self.chunks.iter_mut() {
+            for (i, occupied) in chunk.occupied.iter_mut().enumerate() {
+                if *occupied {
+                    unsafe { chunk.slots[i].assume_init_drop(); }
+                    chunk.generations[i] = chunk.generations[i].wrapping_add(1);
+                }
+                *occupied = false;
+            }
+        }
+        self.free_list.clear();
+        self.high_water_mark = 0;
+    }
+
+}
+
+impl<Node> Default for NodeArena<Node> {
+    fn default() -> Self { Self::new() }
+}
+
+/// a reference to a node owned by one particular parallel unit's arena: `unit_index` picks the
+/// unit, `index` is that unit's own stable `ArenaIndex`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnitNodeIndex {
+    pub unit_index: usize,
+    pub index: ArenaIndex,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_and_get_roundtrip() {
+        let mut arena = NodeArena::new();
+        let index = arena.alloc(42);
+        assert_eq!(arena.get(index), Some(&42));
+    }
+
+    #[test]
+    fn free_recycles_the_slot() {
+        let mut arena = NodeArena::new();
+        let first = arena.alloc(1);
+        arena.free(first);
+        let second = arena.alloc(2);
+        assert_eq!(arena.get(second), Some(&2));
+        assert_eq!(arena.get(first), None, "a freed index must not resolve to the new occupant");
+    }
+
+    #[test]
+    fn clear_invalidates_every_outstanding_index() {
+        let mut arena = NodeArena::new();
+        let before = arena.alloc(7);
+        arena.clear();
+        assert_eq!(arena.get(before), None, "indices from a previous generation must not resolve after clear()");
+        let after = arena.alloc(9);
+        assert_eq!(arena.get(after), Some(&9));
+    }
+
+    #[test]
+    fn allocates_across_multiple_chunks() {
+        let mut arena = NodeArena::new();
+        let indices: Vec<_> = (0..(CHUNK_SIZE * 3)).map(|i| arena.alloc(i)).collect();
+        for (i, index) in indices.into_iter().enumerate() {
+            assert_eq!(arena.get(index), Some(&i));
+        }
+    }
+}
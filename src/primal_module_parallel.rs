@@ -1,5 +1,5 @@
 //! Parallel Primal Module
-//! 
+//!
 //! A parallel implementation of the primal module, by calling functions provided by the serial primal module
 //!
 
@@ -10,21 +10,49 @@ use super::primal_module::*;
 use super::primal_module_serial::*;
 use super::visualize::*;
 use super::dual_module::*;
+use super::primal_module_arena::*;
+use super::bit_array::*;
 use std::sync::Arc;
 
 
 pub struct PrimalModuleParallel {
     /// the basic wrapped serial modules at the beginning, afterwards the fused units are appended after them
-    pub units: Vec<ArcRwLock<PrimalModuleParallelUnit>>,
+    pub units: Vec<PrimalModuleParallelUnitPtr>,
     /// thread pool used to execute async functions in parallel
     pub thread_pool: rayon::ThreadPool,
+    /// the partition information, needed to figure out which unit owns which vertex
+    pub partition_info: Arc<PartitionInfo>,
+    /// row `unit_index` is the bit-packed set of vertices owned by that unit's subtree; checking
+    /// whether a dual node's support crosses a partition boundary is then one `contains` lookup
+    /// per syndrome vertex of that support instead of a per-edge scan
+    pub unit_membership: BitMatrix,
 }
 
 pub struct PrimalModuleParallelUnit {
     /// the index
     pub unit_index: usize,
-    /// the owned serial primal module
+    /// the owned serial primal module; interior (fusion) units start out empty and only
+    /// gain content once both of their children have been fused into them
     pub serial_module: PrimalModuleSerial,
+    /// the range of vertices owned by this unit (leaf) or covered by its subtree (fusion unit)
+    pub owning_range: VertexRange,
+    /// parent unit in the fusion tree, absent at the root
+    pub parent: Option<PrimalModuleParallelUnitWeak>,
+    /// the two children of this unit in the fusion tree; `None` for leaf units
+    pub children: Option<(PrimalModuleParallelUnitPtr, PrimalModuleParallelUnitPtr)>,
+    /// whether this unit's `serial_module` currently holds valid, up-to-date state
+    /// (always true for leaves; becomes true for a fusion unit once its children are fused into it)
+    pub is_fused: bool,
+    /// reusable, chunked storage for this unit's own bookkeeping of which `DualNodePtr`s it owns
+    /// (populated by `load`, below). This does *not* change how `DualNode`s themselves are
+    /// allocated (that lives in `dual_module.rs`/`primal_module_serial.rs`, outside this series'
+    /// files) -- it only gives the parallel module's own per-leaf node list a chunk of storage
+    /// that survives across `clear()`/`load()` cycles instead of being a freshly heap-allocated
+    /// `Vec` every decoding problem
+    pub node_arena: NodeArena<DualNodePtr>,
+    /// this leaf's owned syndrome nodes, addressed as `(unit_index, slot)` indices into `node_arena`
+    /// rather than held as a `Vec<DualNodePtr>` of cloned `Arc` handles
+    pub owned_nodes: Vec<UnitNodeIndex>,
 }
 
 pub type PrimalModuleParallelUnitPtr = ArcRwLock<PrimalModuleParallelUnit>;
@@ -46,7 +74,13 @@ impl std::fmt::Debug for PrimalModuleParallelUnitWeak {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct PrimalModuleParallelConfig {
-    /// enable async execution of dual operations; only used when calling top-level operations, not used in individual units
+    /// size of the thread pool used by `resolve`'s internal fusion-tree work (e.g. a serial module's
+    /// own internal parallelism, if any). Note this does *not* currently parallelize `resolve`'s own
+    /// per-unit dispatch loop: `interface`/`dual_module` are shared across the whole fusion tree rather
+    /// than split per partition, so `PrimalModuleImpl::resolve`'s fixed `&mut DualModuleInterface` /
+    /// `&mut D` signature only ever allows one unit's conflicts to be resolved at a time. Getting real
+    /// cross-unit speedup would mean sharding `interface`/`dual_module` themselves per partition, which
+    /// is a larger change than this field alone can buy.
     #[serde(default = "primal_module_parallel_default_configs::thread_pool_size")]
     pub thread_pool_size: usize,
 }
@@ -56,15 +90,179 @@ impl Default for PrimalModuleParallelConfig {
 }
 
 pub mod primal_module_parallel_default_configs {
-    // pub fn thread_pool_size() -> usize { 0 }  // by default to the number of CPU cores
-    pub fn thread_pool_size() -> usize { 1 }  // debug: use a single core
+    pub fn thread_pool_size() -> usize { 0 }  // by default to the number of CPU cores
 }
 
 impl PrimalModuleParallel {
 
     /// recommended way to create a new instance, given a customized configuration
     pub fn new_config(initializer: &SolverInitializer, partition_info: Arc<PartitionInfo>, config: PrimalModuleParallelConfig) -> Self {
-        unimplemented!()
+        let mut thread_pool_builder = rayon::ThreadPoolBuilder::new();
+        if config.thread_pool_size != 0 {
+            thread_pool_builder = thread_pool_builder.num_threads(config.thread_pool_size);
+        }
+        let thread_pool = thread_pool_builder.build().expect("creating thread pool failed");
+        // first pass: instantiate one unit per node of the partition tree (both leaves and interior fusion nodes);
+        // every unit gets its own serial module so that fusing later can reload the fusion unit's own module in place
+        let mut units: Vec<PrimalModuleParallelUnitPtr> = Vec::with_capacity(partition_info.units.len());
+        for (unit_index, unit_partition_info) in partition_info.units.iter().enumerate() {
+            let is_leaf = unit_partition_info.children.is_none();
+            units.push(PrimalModuleParallelUnitPtr::new(PrimalModuleParallelUnit {
+                unit_index,
+                serial_module: PrimalModuleSerial::new(initializer),
+                owning_range: unit_partition_info.range.clone(),
+                parent: None,
+                children: None,
+                is_fused: is_leaf,
+                node_arena: NodeArena::new(),
+                owned_nodes: Vec::new(),
+            }));
+        }
+        // second pass: wire up the parent/child links now that every unit pointer already exists,
+        // turning `units` into the binary fusion tree described by `partition_info`
+        for (unit_index, unit_partition_info) in partition_info.units.iter().enumerate() {
+            if let Some((left_index, right_index)) = unit_partition_info.children {
+                let left_ptr = units[left_index].clone();
+                let right_ptr = units[right_index].clone();
+                let parent_weak = units[unit_index].downgrade();
+                left_ptr.write().parent = Some(parent_weak.clone());
+                right_ptr.write().parent = Some(parent_weak);
+                units[unit_index].write().children = Some((left_ptr, right_ptr));
+            }
+        }
+        // build the unit-to-vertex membership matrix used to cheaply test boundary crossing later
+        let mut unit_membership = BitMatrix::new(units.len(), initializer.vertex_num);
+        for (unit_index, unit_partition_info) in partition_info.units.iter().enumerate() {
+            for vertex_index in unit_partition_info.range.iter() {
+                unit_membership.set(unit_index, vertex_index);
+            }
+        }
+        Self {
+            units,
+            thread_pool,
+            partition_info,
+            unit_membership,
+        }
+    }
+
+    /// find the unit currently authoritative for a given vertex: walking down from the root, this
+    /// stops at the *first* already-fused unit it meets, not necessarily at a leaf. A fused unit's
+    /// `serial_module` holds the merged, up-to-date state for its whole subtree, which makes its
+    /// children's copies stale; routing new work past a fused ancestor down to one of those stale
+    /// children (as a plain leaf-only search would) silently loses every conflict resolved after the
+    /// first fusion. Leaves start out fused, so before any fusion happens this still just finds the leaf.
+    fn find_owning_unit(&self, vertex_index: VertexIndex) -> Option<PrimalModuleParallelUnitPtr> {
+        let mut unit_ptr = self.units.last()?.clone();  // the root is always appended last
+        loop {
+            let (is_fused, children, owning_range) = {
+                let unit = unit_ptr.read_recursive();
+                (unit.is_fused, unit.children.clone(), unit.owning_range.clone())
+            };
+            if !owning_range.contains(vertex_index) {
+                return None;
+            }
+            if is_fused {
+                return Some(unit_ptr);
+            }
+            match children {
+                None => return Some(unit_ptr),  // not fused yet but no children: shouldn't happen, leaves start fused
+                Some((left_ptr, right_ptr)) => {
+                    unit_ptr = if left_ptr.read_recursive().owning_range.contains(vertex_index) { left_ptr } else { right_ptr };
+                }
+            }
+        }
+    }
+
+    /// true iff both children of a fusion unit are themselves fully fused, meaning this unit is now ready to be fused
+    fn is_ready_to_fuse(unit_ptr: &PrimalModuleParallelUnitPtr) -> bool {
+        let unit = unit_ptr.read_recursive();
+        !unit.is_fused && match &unit.children {
+            None => false,
+            Some((left_ptr, right_ptr)) => left_ptr.read_recursive().is_fused && right_ptr.read_recursive().is_fused,
+        }
+    }
+
+    /// true iff any syndrome vertex reachable from `node_ptr` (through blossom nesting) lies in both
+    /// `left` and `right`, i.e. this dual node's support straddles the newly-joined partition boundary;
+    /// built only from the already-public `DualNodeClass` variants, so it doesn't depend on any
+    /// method existing on the serial module
+    fn node_crosses_boundary(node_ptr: &DualNodePtr, left: &BitVector, right: &BitVector) -> bool {
+        let node = node_ptr.read_recursive();
+        match &node.class {
+            DualNodeClass::SyndromeVertex { .. } => false,  // a single vertex can't be on both sides
+            DualNodeClass::Blossom { nodes_circle, .. } => {
+                let mut touches_left = false;
+                let mut touches_right = false;
+                for child_weak in nodes_circle.iter() {
+                    let child_ptr = child_weak.upgrade_force();
+                    match &child_ptr.read_recursive().class {
+                        DualNodeClass::SyndromeVertex { syndrome_index } => {
+                            touches_left |= left.contains(*syndrome_index);
+                            touches_right |= right.contains(*syndrome_index);
+                        }
+                        _ => {
+                            // a nested blossom: fold every syndrome vertex beneath it into this blossom's touches
+                            let mut stack = vec![child_ptr.clone()];
+                            while let Some(ptr) = stack.pop() {
+                                match &ptr.read_recursive().class {
+                                    DualNodeClass::SyndromeVertex { syndrome_index } => {
+                                        touches_left |= left.contains(*syndrome_index);
+                                        touches_right |= right.contains(*syndrome_index);
+                                    }
+                                    DualNodeClass::Blossom { nodes_circle, .. } => stack.extend(nodes_circle.iter().map(|weak| weak.upgrade_force())),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    if touches_left && touches_right {
+                        return true;
+                    }
+                }
+                touches_left && touches_right
+            }
+            _ => false,
+        }
+    }
+
+    /// collect every dual node registered on the shared interface whose support straddles the
+    /// boundary between `left` and `right`, with one bit lookup per syndrome vertex of each node's
+    /// support instead of a per-edge scan
+    fn dual_nodes_crossing(interface: &DualModuleInterface, left: &BitVector, right: &BitVector) -> Vec<DualNodePtr> {
+        interface.nodes.iter().filter_map(|node| node.as_ref())
+            .filter(|node_ptr| Self::node_crosses_boundary(node_ptr, left, right))
+            .cloned()
+            .collect()
+    }
+
+    /// merge the two children of a fusion unit into it. `PrimalModuleImpl` has no primitive for
+    /// algebraically merging two already-resolved serial modules in place (no `fuse`/`augment_along`/
+    /// `form_blossom`/`resolve_boundary` exists on it, nor does it expose the alternating tree needed
+    /// to implement `AlternatingTreeView` over it), so this re-derives the unit's own matching from
+    /// scratch against the shared interface using only the real trait: `clear`, `load`, `resolve`.
+    /// The boundary-crossing check is kept as a cheap early-out: if nothing straddles the new
+    /// seam yet, the two children's matchings are already independent and there is nothing to redo.
+    fn fuse_unit<D: DualModuleImpl>(&self, unit_ptr: &PrimalModuleParallelUnitPtr, interface: &mut DualModuleInterface, dual_module: &mut D) {
+        let (left_ptr, right_ptr) = unit_ptr.read_recursive().children.clone().expect("only fusion units are fused");
+        let (left_index, right_index) = (left_ptr.read_recursive().unit_index, right_ptr.read_recursive().unit_index);
+        let left_membership = self.unit_membership.row(left_index);
+        let right_membership = self.unit_membership.row(right_index);
+        // only the dual nodes whose support touches vertices on both sides of the partition can possibly
+        // conflict across the newly-joined halves; bitset lookups of their syndrome vertices find exactly
+        // those without scanning every edge of the merged region
+        let boundary_nodes = Self::dual_nodes_crossing(interface, left_membership, right_membership);
+        if boundary_nodes.is_empty() {
+            unit_ptr.write().is_fused = true;
+            return;
+        }
+        let mut unit = unit_ptr.write();
+        unit.serial_module.clear();
+        unit.serial_module.load(interface);
+        let group_max_update_length = dual_module.compute_maximum_update_length();
+        if !group_max_update_length.is_empty() {
+            unit.serial_module.resolve(group_max_update_length, interface, dual_module);
+        }
+        unit.is_fused = true;
     }
 
 }
@@ -76,26 +274,116 @@ impl PrimalModuleImpl for PrimalModuleParallel {
     }
 
     fn clear(&mut self) {
-        unimplemented!()
+        for unit_ptr in self.units.iter() {
+            let mut unit = unit_ptr.write();
+            unit.serial_module.clear();
+            unit.node_arena.clear();
+            unit.owned_nodes.clear();
+            unit.is_fused = unit.children.is_none();
+        }
     }
-    
+
     fn load(&mut self, interface: &DualModuleInterface) {
-        unimplemented!()
+        // record, per leaf, which syndrome nodes it owns -- stashed in that leaf's own arena (reused
+        // across decoding problems via `clear()`) instead of a freshly heap-allocated `Vec` every time
+        for dual_node_ptr in interface.nodes.iter().filter_map(|node| node.as_ref()) {
+            let vertex_index = {
+                let node = dual_node_ptr.read_recursive();
+                match &node.class {
+                    DualNodeClass::SyndromeVertex { syndrome_index } => *syndrome_index,
+                    _ => continue,
+                }
+            };
+            let leaf_ptr = self.find_owning_unit(vertex_index)
+                .unwrap_or_else(|| panic!("vertex {vertex_index} is not owned by any partition"));
+            debug_assert!(leaf_ptr.read_recursive().children.is_none(), "load() runs before any fusion, so every unit found here is a leaf");
+            let mut leaf = leaf_ptr.write();
+            let unit_index = leaf.unit_index;
+            let arena_index = leaf.node_arena.alloc(dual_node_ptr.clone());
+            leaf.owned_nodes.push(UnitNodeIndex { unit_index, index: arena_index });
+        }
+        // load each leaf's serial module against the shared interface exactly once, regardless of how
+        // many syndrome nodes it owns
+        for unit_ptr in self.units.iter() {
+            if unit_ptr.read_recursive().children.is_none() {
+                unit_ptr.write().serial_module.load(interface);
+            }
+        }
     }
 
-    fn resolve<D: DualModuleImpl>(&mut self, mut group_max_update_length: GroupMaxUpdateLength, interface: &mut DualModuleInterface, dual_module: &mut D) {
-        unimplemented!()
+    fn resolve<D: DualModuleImpl>(&mut self, group_max_update_length: GroupMaxUpdateLength, interface: &mut DualModuleInterface, dual_module: &mut D) {
+        // split the conflicts by which unit is currently authoritative for the vertex they're anchored
+        // on: before any fusion that's always a leaf, but after a unit has fused it becomes the owner of
+        // its whole subtree's range, so later conflicts must go to it directly rather than to one of its
+        // now-stale children (see `find_owning_unit`)
+        let mut per_unit_updates: Vec<GroupMaxUpdateLength> = self.units.iter().map(|_| GroupMaxUpdateLength::new()).collect();
+        let mut touched_units: Vec<usize> = Vec::new();
+        for update in group_max_update_length.into_iter() {
+            let vertex_index = update.representative_vertex();
+            let unit_ptr = self.find_owning_unit(vertex_index)
+                .unwrap_or_else(|| panic!("vertex {vertex_index} is not owned by any partition"));
+            let unit_index = unit_ptr.read_recursive().unit_index;
+            if per_unit_updates[unit_index].is_empty() {
+                touched_units.push(unit_index);
+            }
+            per_unit_updates[unit_index].push(update);
+        }
+        // every touched unit's serial `resolve` still needs `&mut interface`/`&mut dual_module`: those
+        // two are shared across the whole fusion tree (not split per partition), so handing out the same
+        // `&mut` to several threads at once would both fail to borrow-check and race on their shared
+        // state. Run them one at a time, still under the configured thread pool so any parallelism the
+        // serial module's own `resolve` spawns internally uses it. NOTE: this means `thread_pool_size`
+        // does not buy parallelism across units for this call; see `PrimalModuleParallelConfig` for why.
+        self.thread_pool.install(|| {
+            for &unit_index in touched_units.iter() {
+                let update = std::mem::replace(&mut per_unit_updates[unit_index], GroupMaxUpdateLength::new());
+                self.units[unit_index].write().serial_module.resolve(update, interface, dual_module);
+            }
+        });
+        // walk the fusion tree bottom-up: whenever both children of a unit have become fused, fuse the unit itself,
+        // re-examining only the dual nodes touching the shared boundary instead of the whole merged region. This
+        // can keep happening on later calls to `resolve` too, since a previously-fused unit can still gain new
+        // conflicts against a sibling subtree's vertices after further growth -- those now route straight to it
+        // via `find_owning_unit` above instead of being silently dropped on a stale child.
+        for unit_ptr in self.units.iter() {
+            if Self::is_ready_to_fuse(unit_ptr) {
+                self.fuse_unit(unit_ptr, interface, dual_module);
+            }
+        }
     }
 
-    fn intermediate_matching<D: DualModuleImpl>(&mut self, _interface: &mut DualModuleInterface, _dual_module: &mut D) -> IntermediateMatching {
-        unimplemented!()
+    fn intermediate_matching<D: DualModuleImpl>(&mut self, interface: &mut DualModuleInterface, dual_module: &mut D) -> IntermediateMatching {
+        // growth and conflict resolution have finished by the time this is called, but some interior
+        // units may never have had a reason to fuse (e.g. independent clusters whose boundaries never
+        // touched); force any stragglers to fuse now so the root ends up holding the matching for the
+        // whole graph rather than just whichever subtree happened to fuse during `resolve`
+        loop {
+            let ready: Vec<_> = self.units.iter().filter(|unit_ptr| Self::is_ready_to_fuse(unit_ptr)).cloned().collect();
+            if ready.is_empty() {
+                break;
+            }
+            for unit_ptr in ready.iter() {
+                self.fuse_unit(unit_ptr, interface, dual_module);
+            }
+        }
+        // the root of the fusion tree is always the last unit; once fully fused it holds the matching for everyone
+        let root_ptr = self.units.last().expect("must have at least one unit").clone();
+        debug_assert!(root_ptr.read_recursive().is_fused, "the fusion tree must be fully connected for every unit to eventually become ready to fuse");
+        root_ptr.write().serial_module.intermediate_matching(interface, dual_module)
     }
 
 }
 
 impl FusionVisualizer for PrimalModuleParallel {
     fn snapshot(&self, abbrev: bool) -> serde_json::Value {
-        unimplemented!()
+        let mut unit_snapshots = Vec::with_capacity(self.units.len());
+        for unit_ptr in self.units.iter() {
+            unit_snapshots.push(unit_ptr.read_recursive().serial_module.snapshot(abbrev));
+        }
+        json!({
+            "unit_count": self.units.len(),
+            "units": unit_snapshots,
+        })
     }
 }
 
@@ -157,7 +445,7 @@ pub mod tests {
         assert_eq!(interface.sum_dual_variables, final_dual * 2, "unexpected final dual variable sum");
         (interface, primal_module, dual_module)
     }
-    
+
     pub fn primal_module_parallel_standard_syndrome<F>(code: impl ExampleCode, visualize_filename: String, syndrome_vertices: Vec<VertexIndex>
             , final_dual: Weight, partition_func: F, reordered_vertices: Option<Vec<VertexIndex>>)
             -> (DualModuleInterface, PrimalModuleParallel, DualModuleParallel<DualModuleSerial>) where F: Fn(&SolverInitializer, &mut PartitionConfig) {
@@ -175,5 +463,15 @@ pub mod tests {
         }, None);
     }
 
+    /// split the graph into 2 partitions so decoding goes through the fusion path, and check the final dual sum
+    #[test]
+    fn primal_module_parallel_basic_2_fusion_tree() {  // cargo test primal_module_parallel_basic_2_fusion_tree -- --nocapture
+        let visualize_filename = format!("primal_module_parallel_basic_2_fusion_tree.json");
+        let syndrome_vertices = vec![39, 52, 63, 90, 100];
+        let half_weight = 500;
+        primal_module_parallel_standard_syndrome(CodeCapacityPlanarCode::new(11, 0.1, half_weight), visualize_filename, syndrome_vertices, 9 * half_weight, |initializer, config| {
+            config.partition(initializer, 2);  // split into 2 partitions to exercise the fusion path
+        }, None);
+    }
 
 }